@@ -2,29 +2,50 @@ use std::error::Error;
 use rustfft::FftPlanner;
 use rustfft::num_complex::Complex;
 use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
 use std::hash::{Hash, Hasher};
-use crate::fingerprint::AudioFingerprint;
+use crate::fingerprint::{AudioFingerprint, HashPoint};
 
 pub struct AudioProcessor {
     pub sample_rate: u32,
     pub window_size: usize,
     pub overlap: usize,
+    pub peaks_per_tile: usize,   // Top-K peaks kept per time/frequency tile
+    pub tile_time_frames: usize, // Tile height, in spectrogram frames
+    pub tile_freq_bins: usize,   // Tile width, in frequency bins
 }
 
 impl AudioProcessor {
     pub fn new(sample_rate: u32) -> Self {
         AudioProcessor {
             sample_rate,
-            window_size: 2048,  // Typical FFT window size
-            overlap: 1024,      // 50% overlap between windows
+            window_size: 2048,     // Typical FFT window size
+            overlap: 1024,         // 50% overlap between windows
+            peaks_per_tile: 5,     // Top-K peaks kept per tile
+            tile_time_frames: 20,  // ~20 STFT frames per tile
+            tile_freq_bins: 64,    // Frequency bins per tile
         }
     }
 
-    pub fn process_audio(&self, audio_data: &[f32]) -> Result<AudioFingerprint, Box<dyn Error>> {
+    // source_sample_rate is the rate the samples were decoded at; if it
+    // differs from self.sample_rate, the audio is resampled first.
+    pub fn process_audio(&self, audio_data: &[f32], source_sample_rate: u32) -> Result<AudioFingerprint, Box<dyn Error>> {
+        let resampled;
+        let audio_data = if source_sample_rate == self.sample_rate {
+            audio_data
+        } else {
+            resampled = self.resample(audio_data, source_sample_rate);
+            &resampled
+        };
+
+        if audio_data.len() <= self.window_size {
+            return Ok(AudioFingerprint { peaks: vec![], hashes: vec![] });
+        }
+
         // Create FFT planner
         let mut planner = FftPlanner::new();
         let fft = planner.plan_fft_forward(self.window_size);
-        
+
         // Process audio in overlapping windows
         let mut spectrogram = Vec::new();
         let mut i = 0;
@@ -60,34 +81,82 @@ impl AudioProcessor {
         let peaks = self.find_peaks(&spectrogram);
         
         // Generate hash from peaks
-        let hash = self.generate_hash(&peaks);
-        
-        Ok(AudioFingerprint { peaks, hash })
+        let hashes = self.generate_hash(&peaks);
+
+        Ok(AudioFingerprint { peaks, hashes })
     }
     
+    // Linearly resamples audio_data from source_rate to self.sample_rate.
+    pub fn resample(&self, audio_data: &[f32], source_rate: u32) -> Vec<f32> {
+        if source_rate == self.sample_rate || audio_data.is_empty() {
+            return audio_data.to_vec();
+        }
+
+        let ratio = self.sample_rate as f64 / source_rate as f64;
+        let output_len = (audio_data.len() as f64 * ratio).round() as usize;
+        let last_idx = audio_data.len() - 1;
+
+        (0..output_len)
+            .map(|i| {
+                let src_pos = i as f64 / ratio;
+                let idx = (src_pos.floor() as usize).min(last_idx);
+                let frac = (src_pos - idx as f64) as f32;
+
+                let s0 = audio_data[idx];
+                let s1 = audio_data[(idx + 1).min(last_idx)];
+                s0 + (s1 - s0) * frac
+            })
+            .collect()
+    }
+
+    // Keeps only the top peaks_per_tile local maxima per time/frequency
+    // tile, with a running amplitude mean as the acceptance bar, instead of
+    // a flat threshold.
     pub fn find_peaks(&self, spectrogram: &[Vec<f32>]) -> Vec<(f32, f32)> {
-        let mut peaks = Vec::new();
         let neighborhood_size = 10; // Size of the neighborhood to check for local maxima
-        
+        if spectrogram.len() <= 2 * neighborhood_size {
+            return Vec::new();
+        }
+
+        let mut running_mean = 0.0f32;
+        let mut running_count = 0u32;
+        let mut tiles: HashMap<(usize, usize), Vec<(f32, f32, f32)>> = HashMap::new();
+
         for t in neighborhood_size..(spectrogram.len() - neighborhood_size) {
             for f in neighborhood_size..(spectrogram[t].len() - neighborhood_size) {
-                if self.is_local_maximum(spectrogram, f, t) {
-                    let freq = f as f32 * self.sample_rate as f32 / (2.0 * self.window_size as f32);
-                    let time = t as f32 * self.overlap as f32 / self.sample_rate as f32;
-                    peaks.push((freq, time));
+                let magnitude = spectrogram[t][f];
+                running_count += 1;
+                running_mean += (magnitude - running_mean) / running_count as f32;
+
+                if magnitude < running_mean || !self.is_local_maximum(spectrogram, f, t) {
+                    continue;
                 }
+
+                let freq = f as f32 * self.sample_rate as f32 / (2.0 * self.window_size as f32);
+                let time = t as f32 * self.overlap as f32 / self.sample_rate as f32;
+                let tile_key = (t / self.tile_time_frames, f / self.tile_freq_bins);
+                tiles.entry(tile_key).or_default().push((freq, time, magnitude));
             }
         }
+
+        let mut peaks = Vec::new();
+        for mut bucket in tiles.into_values() {
+            bucket.sort_by(|a, b| b.2.partial_cmp(&a.2).unwrap());
+            bucket.truncate(self.peaks_per_tile);
+            peaks.extend(bucket.into_iter().map(|(freq, time, _)| (freq, time)));
+        }
+
+        // generate_hash pairs each peak with the ones shortly after it in
+        // time, so restore time order after pruning per-tile. Break ties on
+        // freq too: many peaks share the same t (and so the same time), and
+        // tile iteration order (a HashMap) is randomized per process, so a
+        // time-only sort would make the hash output non-deterministic.
+        peaks.sort_by(|a, b| a.1.partial_cmp(&b.1).unwrap().then(a.0.partial_cmp(&b.0).unwrap()));
         peaks
     }
 
     pub fn is_local_maximum(&self, spectrogram: &[Vec<f32>], f: usize, t: usize) -> bool {
         let current = spectrogram[t][f];
-        let threshold = 0.1; // Minimum amplitude threshold
-        
-        if current < threshold {
-            return false;
-        }
 
         // Check neighborhood
         for dt in -3..=3 {
@@ -107,19 +176,19 @@ impl AudioProcessor {
         true
     }
 
-    pub fn generate_hash(&self, peaks: &[(f32, f32)]) -> Vec<u64> {
+    pub fn generate_hash(&self, peaks: &[(f32, f32)]) -> Vec<HashPoint> {
         let mut hashes = Vec::new();
         let fan_out = 5; // Number of target points to pair with each anchor point
-        
+
         for (i, &anchor) in peaks.iter().enumerate() {
             for j in 1..=fan_out {
                 if i + j >= peaks.len() {
                     break;
                 }
-                
+
                 let target = peaks[i + j];
                 let time_delta = target.1 - anchor.1;
-                
+
                 // Create hash using anchor frequency, target frequency, and time delta
                 let hash = {
                     let mut h = DefaultHasher::new();
@@ -128,8 +197,9 @@ impl AudioProcessor {
                     (time_delta as u32).hash(&mut h);
                     h.finish()
                 };
-                
-                hashes.push(hash);
+
+                // Keep the anchor time so matching can align hits by offset
+                hashes.push(HashPoint { hash, anchor_time: anchor.1 });
             }
         }
         hashes
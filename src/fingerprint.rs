@@ -1,7 +1,13 @@
 use serde::{Serialize, Deserialize};
 
+#[derive(Serialize, Deserialize, Clone, Copy)]
+pub struct HashPoint {
+    pub hash: u64,          // Fingerprint hash
+    pub anchor_time: f32,   // Anchor time (seconds) of the point pair that produced it
+}
+
 #[derive(Serialize, Deserialize)]
 pub struct AudioFingerprint {
-    pub peaks: Vec<(f32, f32)>,  // (frequency, time) pairs
-    pub hash: Vec<u64>,          // Fingerprint hash
-}
\ No newline at end of file
+    pub peaks: Vec<(f32, f32)>,    // (frequency, time) pairs
+    pub hashes: Vec<HashPoint>,    // Fingerprint hashes with anchor times
+}
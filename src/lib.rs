@@ -0,0 +1,3 @@
+pub mod audio_processor;
+pub mod fingerprint;
+pub mod matcher;
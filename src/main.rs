@@ -1,14 +1,20 @@
 use std::error::Error;
 use std::path::Path;
 use std::fs;
-use hound::WavReader;
+use std::fs::File;
 use clap::{Parser, Subcommand};
+use symphonia::core::audio::{SampleBuffer, Signal};
+use symphonia::core::codecs::{DecoderOptions, CODEC_TYPE_NULL};
+use symphonia::core::errors::Error as SymphoniaError;
+use symphonia::core::formats::FormatOptions;
+use symphonia::core::io::MediaSourceStream;
+use symphonia::core::meta::MetadataOptions;
+use symphonia::core::probe::Hint;
 use singerprint::{
     audio_processor::AudioProcessor,
     fingerprint::AudioFingerprint,
     matcher::FingerprintMatcher,
 };
-use std::collections::HashMap;
 
 #[derive(Parser)]
 #[command(name = "singerprint")]
@@ -45,15 +51,25 @@ enum Commands {
         /// Audio file to fingerprint
         #[arg(short, long)]
         input: String,
-        
+
         /// Name to associate with the fingerprint
         #[arg(short, long)]
         name: String,
-        
+
         /// Database file to add the fingerprint to
         #[arg(short, long)]
         database: String,
     },
+    /// Find a shared contiguous segment between an audio file and the database
+    MatchSegment {
+        /// Audio file to match
+        #[arg(short, long)]
+        input: String,
+
+        /// Database file containing fingerprints
+        #[arg(short, long)]
+        database: String,
+    },
 }
 
 fn save_fingerprint(fingerprint: &AudioFingerprint, path: &str) -> Result<(), Box<dyn Error>> {
@@ -62,21 +78,6 @@ fn save_fingerprint(fingerprint: &AudioFingerprint, path: &str) -> Result<(), Bo
     Ok(())
 }
 
-fn load_database(path: &str) -> Result<HashMap<String, AudioFingerprint>, Box<dyn Error>> {
-    if Path::new(path).exists() {
-        let content = fs::read_to_string(path)?;
-        Ok(serde_json::from_str(&content)?)
-    } else {
-        Ok(HashMap::new())
-    }
-}
-
-fn save_database(database: &HashMap<String, AudioFingerprint>, path: &str) -> Result<(), Box<dyn Error>> {
-    let json = serde_json::to_string_pretty(database)?;
-    fs::write(path, json)?;
-    Ok(())
-}
-
 fn main() -> Result<(), Box<dyn Error>> {
     let cli = Cli::parse();
     let processor = AudioProcessor::new(44100);
@@ -94,43 +95,125 @@ fn main() -> Result<(), Box<dyn Error>> {
         }
         
         Commands::Match { input, database } => {
-            let database_content = load_database(&database)?;
-            let mut matcher = FingerprintMatcher::new();
-            
-            // Add all fingerprints from the database
-            for (name, fp) in database_content {
-                matcher.add_fingerprint(&name, fp);
-            }
-            
+            let matcher = FingerprintMatcher::load_or_new(&database)?;
             let query_fingerprint = process_file(&input, &processor)?;
             
-            if let Some(match_name) = matcher.find_match(&query_fingerprint) {
-                println!("Match found: {}", match_name);
+            if let Some(result) = matcher.find_match(&query_fingerprint) {
+                println!(
+                    "Match found: {} (offset: {:.2}s, score: {})",
+                    result.song, result.offset, result.score
+                );
             } else {
                 println!("No match found");
             }
         }
         
         Commands::Add { input, name, database } => {
-            let mut database_content = load_database(&database)?;
+            let mut matcher = FingerprintMatcher::load_or_new(&database)?;
             let fingerprint = process_file(&input, &processor)?;
-            
-            database_content.insert(name.clone(), fingerprint);
-            save_database(&database_content, &database)?;
-            
+
+            matcher.add_fingerprint(&name, fingerprint);
+            matcher.save(&database)?;
+
             println!("Added fingerprint for '{}' to database: {}", name, database);
         }
+
+        Commands::MatchSegment { input, database } => {
+            let matcher = FingerprintMatcher::load_or_new(&database)?;
+            let query_fingerprint = process_file(&input, &processor)?;
+
+            if let Some(segment) = matcher.find_segment_match(&query_fingerprint) {
+                println!(
+                    "Segment match found: {} ({:.2}s shared, query@{:.2}s, stored@{:.2}s)",
+                    segment.song, segment.duration, segment.query_start, segment.stored_start
+                );
+            } else {
+                println!("No segment match found");
+            }
+        }
     }
     
     Ok(())
 }
 
 fn process_file(path: &str, processor: &AudioProcessor) -> Result<AudioFingerprint, Box<dyn Error>> {
-    let mut reader = WavReader::open(Path::new(path))?;
-    let samples: Vec<f32> = reader
-        .samples::<i16>()
-        .map(|s| s.unwrap() as f32 / i16::MAX as f32)
-        .collect();
-    
-    processor.process_audio(&samples)
+    let (samples, sample_rate) = decode_audio(Path::new(path))?;
+    processor.process_audio(&samples, sample_rate)
+}
+
+/// Decodes any symphonia-supported audio file (MP3, FLAC, OGG, AAC, WAV, ...)
+/// into a mono `f32` sample buffer, downmixing multichannel audio by
+/// averaging channels. Returns the decoded samples along with the stream's
+/// native sample rate.
+fn decode_audio(path: &Path) -> Result<(Vec<f32>, u32), Box<dyn Error>> {
+    let file = File::open(path)?;
+    let mss = MediaSourceStream::new(Box::new(file), Default::default());
+
+    let mut hint = Hint::new();
+    if let Some(ext) = path.extension().and_then(|e| e.to_str()) {
+        hint.with_extension(ext);
+    }
+
+    let probed = symphonia::default::get_probe().format(
+        &hint,
+        mss,
+        &FormatOptions::default(),
+        &MetadataOptions::default(),
+    )?;
+    let mut format = probed.format;
+
+    let track = format
+        .tracks()
+        .iter()
+        .find(|t| t.codec_params.codec != CODEC_TYPE_NULL)
+        .ok_or("no supported audio track found")?;
+    let track_id = track.id;
+
+    let mut decoder = symphonia::default::get_codecs()
+        .make(&track.codec_params, &DecoderOptions::default())?;
+
+    let mut sample_rate = track.codec_params.sample_rate.unwrap_or(44100);
+    let mut samples = Vec::new();
+    let mut sample_buf: Option<SampleBuffer<f32>> = None;
+
+    loop {
+        let packet = match format.next_packet() {
+            Ok(packet) => packet,
+            Err(SymphoniaError::IoError(_)) | Err(SymphoniaError::ResetRequired) => break,
+            Err(e) => return Err(Box::new(e)),
+        };
+
+        if packet.track_id() != track_id {
+            continue;
+        }
+
+        match decoder.decode(&packet) {
+            Ok(decoded) => {
+                let spec = *decoded.spec();
+                sample_rate = spec.rate;
+                let channels = spec.channels.count();
+
+                if sample_buf.is_none() {
+                    sample_buf = Some(SampleBuffer::new(decoded.capacity() as u64, spec));
+                }
+
+                let buf = sample_buf.as_mut().unwrap();
+                buf.copy_interleaved_ref(decoded);
+
+                // Downmix to mono by averaging channels
+                for frame in buf.samples().chunks(channels) {
+                    let sum: f32 = frame.iter().sum();
+                    samples.push(sum / channels as f32);
+                }
+            }
+            Err(SymphoniaError::DecodeError(_)) => continue,
+            Err(e) => return Err(Box::new(e)),
+        }
+    }
+
+    if samples.is_empty() {
+        return Err("no audio samples decoded".into());
+    }
+
+    Ok((samples, sample_rate))
 }
\ No newline at end of file
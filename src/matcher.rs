@@ -1,49 +1,289 @@
 use std::collections::HashMap;
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+use serde::{Serialize, Deserialize};
 use crate::fingerprint::AudioFingerprint;
 
+pub type SongId = String;
+
+// Width of each time-offset histogram bin, in seconds.
+const OFFSET_BIN_SECONDS: f32 = 0.05;
+
+// A match is considered confident only when its peak-bin count exceeds this.
+const MATCH_THRESHOLD: u32 = 10;
+
+// Width of one sub-fingerprint frame used by the segment matcher, in seconds.
+const FRAME_DURATION_SECONDS: f32 = 0.1;
+
+pub struct MatchResult {
+    pub song: String,
+    pub offset: f32, // Seconds into the stored song where the query begins
+    pub score: u32,  // Height of the winning time-offset histogram bin
+}
+
+pub struct SegmentMatch {
+    pub song: String,
+    pub query_start: f32,  // Seconds into the query where the segment starts
+    pub stored_start: f32, // Seconds into the stored song where the segment starts
+    pub duration: f32,     // Length of the shared segment, in seconds
+}
+
+#[derive(Serialize, Deserialize)]
 pub struct FingerprintMatcher {
-    database: HashMap<String, AudioFingerprint>,
+    database: HashMap<SongId, AudioFingerprint>,
+    // Inverted index: hash -> songs containing it, with the anchor time of
+    // each occurrence. Lets find_match look up each query hash once instead
+    // of scanning every stored fingerprint.
+    index: HashMap<u64, Vec<(SongId, f32)>>,
+    pub minimum_segment_duration: f32, // Minimum segment length (seconds) to report a match
+    pub maximum_difference: f32,       // Max fraction of differing bits allowed in a segment
 }
 
 impl FingerprintMatcher {
     pub fn new() -> Self {
         FingerprintMatcher {
             database: HashMap::new(),
+            index: HashMap::new(),
+            minimum_segment_duration: 5.0,
+            maximum_difference: 0.35,
+        }
+    }
+
+    pub fn load_or_new(path: &str) -> Result<Self, Box<dyn Error>> {
+        if Path::new(path).exists() {
+            let content = fs::read_to_string(path)?;
+            Ok(serde_json::from_str(&content)?)
+        } else {
+            Ok(Self::new())
         }
     }
-    
+
+    pub fn save(&self, path: &str) -> Result<(), Box<dyn Error>> {
+        let json = serde_json::to_string_pretty(self)?;
+        fs::write(path, json)?;
+        Ok(())
+    }
+
     pub fn add_fingerprint(&mut self, name: &str, fingerprint: AudioFingerprint) {
-        self.database.insert(name.to_string(), fingerprint);
+        let song_id: SongId = name.to_string();
+
+        // Re-adding an existing song must not leave its old hashes behind.
+        if self.database.contains_key(&song_id) {
+            for entries in self.index.values_mut() {
+                entries.retain(|(song, _)| song != &song_id);
+            }
+        }
+
+        for point in &fingerprint.hashes {
+            self.index
+                .entry(point.hash)
+                .or_default()
+                .push((song_id.clone(), point.anchor_time));
+        }
+
+        self.database.insert(song_id, fingerprint);
     }
-    
-    pub fn find_match(&self, fingerprint: &AudioFingerprint) -> Option<String> {
-        let mut best_match = None;
-        let mut best_score = 0;
-        
-        for (name, stored) in &self.database {
-            let score = self.compare_fingerprints(fingerprint, stored);
-            if score > best_score {
-                best_score = score;
-                best_match = Some(name.clone());
+
+    pub fn find_match(&self, fingerprint: &AudioFingerprint) -> Option<MatchResult> {
+        let mut histograms: HashMap<&SongId, HashMap<i64, u32>> = HashMap::new();
+
+        for q in &fingerprint.hashes {
+            let Some(hits) = self.index.get(&q.hash) else {
+                continue;
+            };
+
+            for (song, anchor_time) in hits {
+                let delta = anchor_time - q.anchor_time;
+                let bin = (delta / OFFSET_BIN_SECONDS).round() as i64;
+                *histograms.entry(song).or_default().entry(bin).or_insert(0) += 1;
             }
         }
-        
-        if best_score > 10 {  // Adjust threshold as needed
-            best_match
-        } else {
-            None
+
+        let mut best: Option<MatchResult> = None;
+        for (song, histogram) in histograms {
+            let Some((bin, count)) = histogram.into_iter().max_by_key(|&(_, count)| count) else {
+                continue;
+            };
+
+            if best.as_ref().map_or(true, |b| count > b.score) {
+                best = Some(MatchResult {
+                    song: song.clone(),
+                    offset: bin as f32 * OFFSET_BIN_SECONDS,
+                    score: count,
+                });
+            }
         }
+
+        best.filter(|m| m.score > MATCH_THRESHOLD)
     }
-    
-    pub fn compare_fingerprints(&self, fp1: &AudioFingerprint, fp2: &AudioFingerprint) -> u32 {
-        let mut score = 0;
-        
-        for hash1 in &fp1.hash {
-            if fp2.hash.contains(hash1) {
-                score += 1;
+
+    pub fn find_segment_match(&self, fingerprint: &AudioFingerprint) -> Option<SegmentMatch> {
+        let query_frames = sub_fingerprints(fingerprint);
+        if query_frames.is_empty() {
+            return None;
+        }
+
+        let mut best: Option<SegmentMatch> = None;
+
+        for (name, stored) in &self.database {
+            let stored_frames = sub_fingerprints(stored);
+            let Some((query_start, stored_start, duration)) = best_segment(
+                &query_frames,
+                &stored_frames,
+                self.minimum_segment_duration,
+                self.maximum_difference,
+            ) else {
+                continue;
+            };
+
+            if best.as_ref().map_or(true, |b| duration > b.duration) {
+                best = Some(SegmentMatch { song: name.clone(), query_start, stored_start, duration });
             }
         }
-        
-        score
+
+        best
     }
-}
\ No newline at end of file
+}
+
+// Collapses a fingerprint's hashes into an ordered sequence of per-frame
+// sub-fingerprints, XORing together the hashes that fall in each frame.
+fn sub_fingerprints(fingerprint: &AudioFingerprint) -> Vec<u32> {
+    if fingerprint.hashes.is_empty() {
+        return Vec::new();
+    }
+
+    let max_time = fingerprint
+        .hashes
+        .iter()
+        .map(|p| p.anchor_time)
+        .fold(0.0f32, f32::max);
+
+    let frame_count = (max_time / FRAME_DURATION_SECONDS) as usize + 1;
+    let mut frames = vec![0u32; frame_count];
+
+    for point in &fingerprint.hashes {
+        let frame = (point.anchor_time / FRAME_DURATION_SECONDS) as usize;
+        frames[frame] ^= point.hash as u32;
+    }
+
+    frames
+}
+
+// Slides query against stored at every frame alignment and returns the
+// (query_start, stored_start, duration) of the longest contiguous run
+// within maximum_difference that meets minimum_segment_duration.
+fn best_segment(
+    query: &[u32],
+    stored: &[u32],
+    minimum_segment_duration: f32,
+    maximum_difference: f32,
+) -> Option<(f32, f32, f32)> {
+    let mut best: Option<(usize, usize, usize)> = None; // (query_start, stored_start, frames)
+
+    let min_offset = -(stored.len() as isize) + 1;
+    let max_offset = query.len() as isize - 1;
+
+    for offset in min_offset..=max_offset {
+        let (q_base, s_base) = if offset >= 0 {
+            (offset as usize, 0)
+        } else {
+            (0, (-offset) as usize)
+        };
+        let len = (query.len() - q_base).min(stored.len() - s_base);
+
+        let mut run_start = 0usize;
+        let mut run_len = 0usize;
+        let mut run_sum = 0u32;
+
+        for i in 0..len {
+            let frame_diff = (query[q_base + i] ^ stored[s_base + i]).count_ones();
+            let candidate_sum = run_sum + frame_diff;
+            let candidate_len = run_len + 1;
+
+            if (candidate_sum as f32) / (candidate_len as f32 * 32.0) <= maximum_difference {
+                if run_len == 0 {
+                    run_start = i;
+                }
+                run_sum = candidate_sum;
+                run_len = candidate_len;
+                continue;
+            }
+
+            if run_len > 0 && best.map_or(true, |(_, _, best_len)| run_len > best_len) {
+                best = Some((q_base + run_start, s_base + run_start, run_len));
+            }
+
+            // The run's average just tipped over the threshold; see if this
+            // frame can start a fresh run on its own.
+            if (frame_diff as f32) / 32.0 <= maximum_difference {
+                run_start = i;
+                run_sum = frame_diff;
+                run_len = 1;
+            } else {
+                run_sum = 0;
+                run_len = 0;
+            }
+        }
+
+        if run_len > 0 && best.map_or(true, |(_, _, best_len)| run_len > best_len) {
+            best = Some((q_base + run_start, s_base + run_start, run_len));
+        }
+    }
+
+    best.and_then(|(q_start, s_start, frames)| {
+        let duration = frames as f32 * FRAME_DURATION_SECONDS;
+        (duration >= minimum_segment_duration).then_some((
+            q_start as f32 * FRAME_DURATION_SECONDS,
+            s_start as f32 * FRAME_DURATION_SECONDS,
+            duration,
+        ))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fingerprint::HashPoint;
+
+    #[test]
+    fn find_match_returns_song_for_identical_query() {
+        let hashes: Vec<HashPoint> = (0..15u64)
+            .map(|i| HashPoint { hash: i, anchor_time: i as f32 * 0.01 })
+            .collect();
+
+        let mut matcher = FingerprintMatcher::new();
+        matcher.add_fingerprint("song", AudioFingerprint { peaks: vec![], hashes: hashes.clone() });
+
+        let query = AudioFingerprint { peaks: vec![], hashes };
+        let result = matcher.find_match(&query).expect("expected a match");
+
+        assert_eq!(result.song, "song");
+        assert!(result.offset.abs() < 0.01);
+    }
+
+    #[test]
+    fn find_segment_match_tolerates_a_noisy_frame() {
+        // 10 frames (1.0s) of identical per-frame hashes, except the query
+        // has one frame with 16 differing bits (a noisy/EQ'd frame). The
+        // average difference across the run should still clear the
+        // threshold, so the whole span should match as one segment instead
+        // of being split at the noisy frame.
+        let stored_hashes: Vec<HashPoint> = (0..10u64)
+            .map(|i| HashPoint { hash: i, anchor_time: i as f32 * 0.1 + 0.05 })
+            .collect();
+
+        let mut query_hashes = stored_hashes.clone();
+        query_hashes[5].hash ^= 0xFFFF;
+
+        let mut matcher = FingerprintMatcher::new();
+        matcher.minimum_segment_duration = 0.5;
+        matcher.add_fingerprint("song", AudioFingerprint { peaks: vec![], hashes: stored_hashes });
+
+        let query = AudioFingerprint { peaks: vec![], hashes: query_hashes };
+        let result = matcher.find_segment_match(&query).expect("expected a segment match");
+
+        assert_eq!(result.song, "song");
+        assert!(result.duration > 0.9, "expected the full 1.0s span, got {}", result.duration);
+    }
+}